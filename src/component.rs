@@ -0,0 +1,629 @@
+extern crate hyper;
+extern crate hyper_timeout;
+
+use crate::engine::App;
+#[cfg(not(feature = "legacy-vec-pipeline"))]
+use crate::engine::Stage;
+use crate::macros::{MiddleWare, ResponseAction, Spider};
+use futures::future::join_all;
+use hyper::client::HttpConnector;
+use hyper::header::USER_AGENT;
+use hyper::{Body, Client as HyperClient, Method, Request as HyperRequest, Uri};
+use hyper_timeout::TimeoutConnector;
+use log::warn;
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "legacy-vec-pipeline")]
+use std::sync::Mutex;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::OwnedSemaphorePermit;
+use tokio::task;
+
+fn now_secs() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct UserAgent(pub String);
+
+/// a crawl target handed to `Request::gen` to be turned into a `Request`
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Task {
+    pub uri: String,
+}
+
+/// a warmed-up session (cookies, chosen user agent, ...) `Request::gen`
+/// can draw on instead of dispatching cold
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Profile {
+    pub ua: UserAgent,
+    pub cookie: Option<String>,
+}
+
+/// one dispatchable HTTP request
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Request {
+    pub uri: String,
+    pub method: String,
+    pub body: Option<Vec<u8>>,
+    pub able: u64,    // unix-seconds this request becomes eligible to dispatch
+    pub retries: u32, // attempts already spent on this request
+}
+
+impl Request {
+    /// rough byte cost of this request: url + body + headroom for headers,
+    /// `None` when there's nothing to measure (caller falls back to a default)
+    pub fn estimated_size(&self) -> Option<usize> {
+        if self.uri.is_empty() {
+            return None;
+        }
+        let body_len = self.body.as_ref().map(|b| b.len()).unwrap_or(0);
+        Some(self.uri.len() + body_len + 256)
+    }
+
+    /// drain up to `round_task` tasks and turn each into a ready-to-dispatch request
+    #[cfg(feature = "legacy-vec-pipeline")]
+    pub fn gen<Entity>(app: &App<Entity>, round_task: usize) {
+        let now = now_secs();
+        let mut task = app.task.lock().unwrap();
+        let mut req = app.req.lock().unwrap();
+        for _ in 0..round_task {
+            let t = match task.pop() {
+                Some(t) => t,
+                None => break,
+            };
+            req.push(Request {
+                uri: t.uri,
+                method: "GET".into(),
+                body: None,
+                able: now,
+                retries: 0,
+            });
+        }
+    }
+
+    #[cfg(not(feature = "legacy-vec-pipeline"))]
+    pub fn gen<Entity>(app: &App<Entity>, round_task: usize) {
+        let now = now_secs();
+        for _ in 0..round_task {
+            let t = match app.task.try_pop() {
+                Some(t) => t,
+                None => break,
+            };
+            let req = Request {
+                uri: t.uri,
+                method: "GET".into(),
+                body: None,
+                able: now,
+                retries: 0,
+            };
+            if let Err(req) = app.req.try_push(req) {
+                // req stage is already at capacity; stop generating this
+                // round rather than blocking Request::gen's caller, and hand
+                // the task back so it is picked up again later
+                let _ = app.task.try_push(Task { uri: req.uri });
+                break;
+            }
+        }
+    }
+}
+
+/// a fetched response, or the terminal failure of a request that exhausted
+/// its retries (`error` set, `status` 0)
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Response {
+    pub req: Request,
+    pub status: u16,
+    pub body: Vec<u8>,
+    pub error: Option<String>,
+}
+
+impl Response {
+    /// drain up to `round_res` responses, running each through
+    /// `MiddleWare::on_response` and, for responses that pass, `Spider::parse`
+    /// followed by `MiddleWare::on_parse_result`. A response with `error` set
+    /// skips the hooks and goes straight to `yield_err`.
+    #[cfg(feature = "legacy-vec-pipeline")]
+    pub fn parse_all<Entity>(
+        app: &mut App<Entity>,
+        round_res: usize,
+        spd: &'static dyn Spider<Entity>,
+        mware: &dyn MiddleWare<Entity>,
+        max_retries: u32,
+        backoff_base_ms: u64,
+    ) {
+        let mut res = app.res.lock().unwrap();
+        let mut result = app.result.lock().unwrap();
+        let mut yield_err = app.yield_err.lock().unwrap();
+        let mut task = app.task.lock().unwrap();
+        let mut req_queue = app.req.lock().unwrap();
+        for _ in 0..round_res {
+            let response = match res.pop() {
+                Some(r) => r,
+                None => break,
+            };
+            if let Some(err) = &response.error {
+                yield_err.push(format!("{}: {}", response.req.uri, err));
+                continue;
+            }
+            match mware.on_response(&response) {
+                ResponseAction::Discard => continue,
+                ResponseAction::Retry => {
+                    // mirrors the backoff/give-up split Client::exec_all
+                    // applies to a transport-level failure
+                    let mut retry_req = response.req;
+                    if retry_req.retries < max_retries {
+                        retry_req.retries += 1;
+                        retry_req.able = backoff_able(retry_req.retries, backoff_base_ms);
+                        req_queue.push(retry_req);
+                    } else {
+                        yield_err.push(format!(
+                            "{}: middleware requested retry after {} retries",
+                            retry_req.uri, retry_req.retries
+                        ));
+                    }
+                }
+                ResponseAction::Continue => {
+                    let (mut items, new_tasks, mut errs) = spd.parse(&response);
+                    mware.on_parse_result(&mut items, &mut errs);
+                    result.extend(items);
+                    yield_err.extend(errs);
+                    task.extend(new_tasks);
+                }
+            }
+        }
+    }
+
+    #[cfg(not(feature = "legacy-vec-pipeline"))]
+    pub fn parse_all<Entity>(
+        app: &mut App<Entity>,
+        round_res: usize,
+        spd: &'static dyn Spider<Entity>,
+        mware: &dyn MiddleWare<Entity>,
+        max_retries: u32,
+        backoff_base_ms: u64,
+    ) {
+        for _ in 0..round_res {
+            let response = match app.res.try_pop() {
+                Some(r) => r,
+                None => break,
+            };
+            if let Some(err) = &response.error {
+                let _ = app
+                    .yield_err
+                    .try_push(format!("{}: {}", response.req.uri, err));
+                continue;
+            }
+            match mware.on_response(&response) {
+                ResponseAction::Discard => continue,
+                ResponseAction::Retry => {
+                    // mirrors the backoff/give-up split Client::exec_all
+                    // applies to a transport-level failure
+                    let mut retry_req = response.req;
+                    if retry_req.retries < max_retries {
+                        retry_req.retries += 1;
+                        retry_req.able = backoff_able(retry_req.retries, backoff_base_ms);
+                        let _ = app.req.try_push(retry_req);
+                    } else {
+                        let _ = app.yield_err.try_push(format!(
+                            "{}: middleware requested retry after {} retries",
+                            retry_req.uri, retry_req.retries
+                        ));
+                    }
+                }
+                ResponseAction::Continue => {
+                    let (mut items, new_tasks, mut errs) = spd.parse(&response);
+                    mware.on_parse_result(&mut items, &mut errs);
+                    for item in items {
+                        let _ = app.result.try_push(item);
+                    }
+                    for e in errs {
+                        let _ = app.yield_err.try_push(e);
+                    }
+                    for t in new_tasks {
+                        let _ = app.task.try_push(t);
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn timeout_client() -> HyperClient<TimeoutConnector<HttpConnector>> {
+    let mut connector = TimeoutConnector::new(HttpConnector::new());
+    connector.set_connect_timeout(Some(Duration::from_secs(10)));
+    connector.set_read_timeout(Some(Duration::from_secs(30)));
+    connector.set_write_timeout(Some(Duration::from_secs(30)));
+    HyperClient::builder().build(connector)
+}
+
+async fn fetch_one(req: &Request) -> Result<(u16, Vec<u8>), String> {
+    let uri: Uri = req.uri.parse().map_err(|e: hyper::http::uri::InvalidUri| e.to_string())?;
+    let method = req.method.parse::<Method>().unwrap_or(Method::GET);
+    let body = match &req.body {
+        Some(b) => Body::from(b.clone()),
+        None => Body::empty(),
+    };
+    let hreq = HyperRequest::builder()
+        .method(method)
+        .uri(uri)
+        .body(body)
+        .map_err(|e| e.to_string())?;
+    let resp = timeout_client().request(hreq).await.map_err(|e| e.to_string())?;
+    let status = resp.status().as_u16();
+    let bytes = hyper::body::to_bytes(resp.into_body())
+        .await
+        .map_err(|e| e.to_string())?;
+    Ok((status, bytes.to_vec()))
+}
+
+/// `able = now + backoff_base_ms * 2^(retries - 1)`, with the delay rounded
+/// down to whole seconds plus one, so every retry waits at least a second
+fn backoff_able(retries: u32, backoff_base_ms: u64) -> u64 {
+    let delay_ms = backoff_base_ms.saturating_mul(1u64 << (retries - 1).min(32));
+    now_secs() + delay_ms / 1000 + 1
+}
+
+pub struct Client;
+
+impl Client {
+    /// fetch every request in `futs`, releasing its matching byte-budget
+    /// permit as soon as that fetch completes. Both a transport-level
+    /// failure and a response whose status is in `retry_statuses` are
+    /// requeued onto `treq` with an exponential-backoff `able`; once
+    /// `max_retries` is exhausted the failure is pushed to `res` instead,
+    /// marked with `error` so `Response::parse_all` routes it to `yield_err`.
+    #[cfg(feature = "legacy-vec-pipeline")]
+    pub async fn exec_all(
+        futs: Vec<Request>,
+        res: Arc<Mutex<Vec<Response>>>,
+        permits: Vec<OwnedSemaphorePermit>,
+        treq: Arc<Mutex<Vec<Request>>>,
+        max_retries: u32,
+        backoff_base_ms: u64,
+        retry_statuses: Arc<Vec<u16>>,
+    ) {
+        let handles: Vec<_> = futs
+            .into_iter()
+            .zip(permits)
+            .map(|(req, permit)| {
+                let res = res.clone();
+                let treq = treq.clone();
+                let retry_statuses = retry_statuses.clone();
+                task::spawn(async move {
+                    let outcome = fetch_one(&req).await;
+                    drop(permit);
+                    let err = match outcome {
+                        Ok((status, body)) if !retry_statuses.contains(&status) => {
+                            res.lock().unwrap().push(Response {
+                                req,
+                                status,
+                                body,
+                                error: None,
+                            });
+                            return;
+                        }
+                        Ok((status, _)) => format!("retryable status {}", status),
+                        Err(err) => err,
+                    };
+                    if req.retries < max_retries {
+                        let mut req = req;
+                        req.retries += 1;
+                        req.able = backoff_able(req.retries, backoff_base_ms);
+                        warn!("retrying {} (attempt {}): {}", req.uri, req.retries, err);
+                        treq.lock().unwrap().push(req);
+                    } else {
+                        warn!("giving up on {} after {} retries: {}", req.uri, req.retries, err);
+                        res.lock().unwrap().push(Response {
+                            req,
+                            status: 0,
+                            body: Vec::new(),
+                            error: Some(err),
+                        });
+                    }
+                })
+            })
+            .collect();
+        join_all(handles).await;
+    }
+
+    #[cfg(not(feature = "legacy-vec-pipeline"))]
+    pub async fn exec_all(
+        futs: Vec<Request>,
+        res: Stage<Response>,
+        permits: Vec<OwnedSemaphorePermit>,
+        treq: Stage<Request>,
+        max_retries: u32,
+        backoff_base_ms: u64,
+        retry_statuses: Arc<Vec<u16>>,
+    ) {
+        let handles: Vec<_> = futs
+            .into_iter()
+            .zip(permits)
+            .map(|(req, permit)| {
+                let res = res.clone();
+                let treq = treq.clone();
+                let retry_statuses = retry_statuses.clone();
+                task::spawn(async move {
+                    let outcome = fetch_one(&req).await;
+                    drop(permit);
+                    let err = match outcome {
+                        Ok((status, body)) if !retry_statuses.contains(&status) => {
+                            res.push_async(Response {
+                                req,
+                                status,
+                                body,
+                                error: None,
+                            })
+                            .await;
+                            return;
+                        }
+                        Ok((status, _)) => format!("retryable status {}", status),
+                        Err(err) => err,
+                    };
+                    if req.retries < max_retries {
+                        let mut req = req;
+                        req.retries += 1;
+                        req.able = backoff_able(req.retries, backoff_base_ms);
+                        warn!("retrying {} (attempt {}): {}", req.uri, req.retries, err);
+                        treq.push_async(req).await;
+                    } else {
+                        warn!("giving up on {} after {} retries: {}", req.uri, req.retries, err);
+                        res.push_async(Response {
+                            req,
+                            status: 0,
+                            body: Vec::new(),
+                            error: Some(err),
+                        })
+                        .await;
+                    }
+                })
+            })
+            .collect();
+        join_all(handles).await;
+    }
+
+    /// drop join handles for futures that have already completed, so
+    /// `fut_res`/`fut_profile` don't grow without bound across rounds
+    pub async fn join(
+        fut_res: crate::engine::JoinHandleList,
+        fut_profile: crate::engine::JoinHandleList,
+    ) {
+        fut_res.lock().unwrap().retain(|(_, jh)| !jh.is_finished());
+        fut_profile.lock().unwrap().retain(|(_, jh)| !jh.is_finished());
+    }
+}
+
+impl Profile {
+    /// warm up `n` sessions against `uri`, one user agent per session
+    /// (cycled from `uas`), capturing any `Set-Cookie` the server hands back
+    #[cfg(feature = "legacy-vec-pipeline")]
+    pub async fn exec_all<Entity>(
+        _spd: &'static dyn Spider<Entity>,
+        stage: Arc<Mutex<Vec<Profile>>>,
+        uri: String,
+        n: usize,
+        uas: Arc<Vec<UserAgent>>,
+    ) {
+        let handles: Vec<_> = (0..n)
+            .map(|i| {
+                let uri = uri.clone();
+                let ua = pick_ua(&uas, i);
+                let stage = stage.clone();
+                task::spawn(async move {
+                    match Profile::warm_up(&uri, &ua).await {
+                        Ok(cookie) => stage.lock().unwrap().push(Profile { ua, cookie }),
+                        Err(err) => warn!("profile warm-up for {} failed: {}", uri, err),
+                    }
+                })
+            })
+            .collect();
+        join_all(handles).await;
+    }
+
+    #[cfg(not(feature = "legacy-vec-pipeline"))]
+    pub async fn exec_all<Entity>(
+        _spd: &'static dyn Spider<Entity>,
+        stage: Stage<Profile>,
+        uri: String,
+        n: usize,
+        uas: Arc<Vec<UserAgent>>,
+    ) {
+        let handles: Vec<_> = (0..n)
+            .map(|i| {
+                let uri = uri.clone();
+                let ua = pick_ua(&uas, i);
+                let stage = stage.clone();
+                task::spawn(async move {
+                    match Profile::warm_up(&uri, &ua).await {
+                        Ok(cookie) => stage.push_async(Profile { ua, cookie }).await,
+                        Err(err) => warn!("profile warm-up for {} failed: {}", uri, err),
+                    }
+                })
+            })
+            .collect();
+        join_all(handles).await;
+    }
+
+    async fn warm_up(uri: &str, ua: &UserAgent) -> Result<Option<String>, String> {
+        let parsed: Uri = uri.parse().map_err(|e: hyper::http::uri::InvalidUri| e.to_string())?;
+        let hreq = HyperRequest::builder()
+            .method(Method::GET)
+            .uri(parsed)
+            .header(USER_AGENT, ua.0.as_str())
+            .body(Body::empty())
+            .map_err(|e| e.to_string())?;
+        let resp = timeout_client().request(hreq).await.map_err(|e| e.to_string())?;
+        let cookie = resp
+            .headers()
+            .get(hyper::header::SET_COOKIE)
+            .and_then(|v| v.to_str().ok())
+            .map(|s| s.to_string());
+        Ok(cookie)
+    }
+}
+
+fn pick_ua(uas: &[UserAgent], i: usize) -> UserAgent {
+    if uas.is_empty() {
+        UserAgent(String::new())
+    } else {
+        uas[i % uas.len()].clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::macros::MiddleWareDefault;
+
+    fn test_request(uri: &str) -> Request {
+        Request {
+            uri: uri.into(),
+            method: "GET".into(),
+            body: None,
+            able: 0,
+            retries: 0,
+        }
+    }
+
+    fn test_response(uri: &str) -> Response {
+        Response {
+            req: test_request(uri),
+            status: 200,
+            body: b"ok".to_vec(),
+            error: None,
+        }
+    }
+
+    struct TestSpider;
+    impl Spider<String> for TestSpider {
+        fn entry_profile(&self) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+            Ok(String::new())
+        }
+        fn entry_task(&self) -> Result<Vec<Task>, Box<dyn std::error::Error + Send + Sync>> {
+            Ok(Vec::new())
+        }
+        fn parse(&self, res: &Response) -> (Vec<String>, Vec<Task>, Vec<String>) {
+            (
+                vec![String::from_utf8_lossy(&res.body).to_string()],
+                Vec::new(),
+                Vec::new(),
+            )
+        }
+    }
+    static SPIDER: TestSpider = TestSpider;
+
+    struct DiscardAll;
+    impl MiddleWare<String> for DiscardAll {
+        fn on_response(&self, _res: &Response) -> ResponseAction {
+            ResponseAction::Discard
+        }
+    }
+
+    struct RetryAll;
+    impl MiddleWare<String> for RetryAll {
+        fn on_response(&self, _res: &Response) -> ResponseAction {
+            ResponseAction::Retry
+        }
+    }
+
+    fn push_response(app: &App<String>, res: Response) {
+        #[cfg(feature = "legacy-vec-pipeline")]
+        app.res.lock().unwrap().push(res);
+        #[cfg(not(feature = "legacy-vec-pipeline"))]
+        app.res.try_push(res).unwrap();
+    }
+
+    fn req_queue_len(app: &App<String>) -> usize {
+        #[cfg(feature = "legacy-vec-pipeline")]
+        return app.req.lock().unwrap().len();
+        #[cfg(not(feature = "legacy-vec-pipeline"))]
+        return app.req.len();
+    }
+
+    fn yield_err_len(app: &App<String>) -> usize {
+        #[cfg(feature = "legacy-vec-pipeline")]
+        return app.yield_err.lock().unwrap().len();
+        #[cfg(not(feature = "legacy-vec-pipeline"))]
+        return app.yield_err.len();
+    }
+
+    fn result_len(app: &App<String>) -> usize {
+        #[cfg(feature = "legacy-vec-pipeline")]
+        return app.result.lock().unwrap().len();
+        #[cfg(not(feature = "legacy-vec-pipeline"))]
+        return app.result.len();
+    }
+
+    #[test]
+    fn estimated_size_empty_uri_is_none() {
+        assert_eq!(test_request("").estimated_size(), None);
+    }
+
+    #[test]
+    fn estimated_size_accounts_for_uri_and_body() {
+        let mut req = test_request("http://example.com");
+        req.body = Some(vec![0u8; 10]);
+        assert_eq!(
+            req.estimated_size(),
+            Some("http://example.com".len() + 10 + 256)
+        );
+    }
+
+    #[test]
+    fn backoff_able_grows_with_retries() {
+        let first = backoff_able(1, 500);
+        let second = backoff_able(2, 500);
+        let third = backoff_able(3, 500);
+        assert!(first >= now_secs());
+        assert!(second >= first);
+        assert!(third >= second);
+    }
+
+    #[test]
+    fn parse_all_discard_drops_the_response() {
+        let mut app: App<String> = App::new();
+        push_response(&app, test_response("http://x"));
+
+        Response::parse_all(&mut app, 10, &SPIDER, &DiscardAll, 3, 500);
+
+        assert_eq!(result_len(&app), 0);
+        assert_eq!(yield_err_len(&app), 0);
+    }
+
+    #[test]
+    fn parse_all_retry_requeues_when_retries_remain() {
+        let mut app: App<String> = App::new();
+        push_response(&app, test_response("http://x"));
+
+        Response::parse_all(&mut app, 10, &SPIDER, &RetryAll, 3, 500);
+
+        assert_eq!(req_queue_len(&app), 1);
+        assert_eq!(yield_err_len(&app), 0);
+    }
+
+    #[test]
+    fn parse_all_retry_yields_err_once_max_retries_exhausted() {
+        let mut app: App<String> = App::new();
+        push_response(&app, test_response("http://x"));
+
+        // retries starts at 0, so max_retries: 0 means no attempts are left
+        Response::parse_all(&mut app, 10, &SPIDER, &RetryAll, 0, 500);
+
+        assert_eq!(req_queue_len(&app), 0);
+        assert_eq!(yield_err_len(&app), 1);
+    }
+
+    #[test]
+    fn parse_all_continue_runs_parse_and_hooks() {
+        let mut app: App<String> = App::new();
+        push_response(&app, test_response("http://x"));
+
+        Response::parse_all(&mut app, 10, &SPIDER, &MiddleWareDefault::new(), 3, 500);
+
+        assert_eq!(result_len(&app), 1);
+    }
+}
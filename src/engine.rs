@@ -1,3 +1,4 @@
+extern crate flume;
 extern crate hyper_timeout;
 extern crate log;
 extern crate serde;
@@ -7,7 +8,7 @@ extern crate tokio;
 
 use crate::component::{Client, Profile, Request, Response, Task, UserAgent};
 use crate::macros::Spider;
-use crate::macros::{MiddleWare, MiddleWareDefault, Pipeline, PipelineDefault};
+use crate::macros::{MiddleWare, MiddleWareDefault, Pipeline, PipelineDefault, RequestAction};
 use futures::future::join_all;
 use log::info;
 use signal_hook::flag as signal_flag;
@@ -15,8 +16,88 @@ use std::sync::{
     atomic::{AtomicUsize, Ordering},
     Arc, Mutex,
 };
+use tokio::sync::Semaphore;
 use tokio::task;
 
+/// fallback byte cost for a request whose size can't be estimated (unknown
+/// body length, headers not built yet, ...)
+const DEFAULT_REQUEST_BYTES: usize = 2 * 1024;
+
+/// `(round started at, join handle)` pairs awaiting cleanup in `Client::join`
+pub type JoinHandleList = Arc<Mutex<Vec<(u64, task::JoinHandle<()>)>>>;
+
+/// rough byte cost of a `Request`, used to size the in-flight semaphore:
+/// url + headers + expected body when known, `DEFAULT_REQUEST_BYTES` otherwise
+fn estimate_request_bytes(req: &Request) -> usize {
+    req.estimated_size().unwrap_or(DEFAULT_REQUEST_BYTES)
+}
+
+/// a bounded MPMC channel backing a single pipeline stage. Replaces a shared
+/// `Arc<Mutex<Vec<T>>>` with a sender/receiver pair so producers
+/// (`Request::gen`, `Profile::exec_all`, `Client::exec_all`) get automatic
+/// backpressure instead of locking a `Vec` that grows without bound.
+///
+/// Compiled out when the `legacy-vec-pipeline` feature is on, which brings
+/// back the `Arc<Mutex<Vec<T>>>` fields for existing `Spider` impls that
+/// poke at them directly; channels are the default pipeline.
+#[cfg(not(feature = "legacy-vec-pipeline"))]
+pub struct Stage<T> {
+    pub tx: flume::Sender<T>,
+    pub rx: flume::Receiver<T>,
+}
+
+#[cfg(not(feature = "legacy-vec-pipeline"))]
+impl<T> Clone for Stage<T> {
+    fn clone(&self) -> Self {
+        Stage {
+            tx: self.tx.clone(),
+            rx: self.rx.clone(),
+        }
+    }
+}
+
+#[cfg(not(feature = "legacy-vec-pipeline"))]
+impl<T> Stage<T> {
+    fn bounded(cap: usize) -> Self {
+        let (tx, rx) = flume::bounded(cap.max(1));
+        Stage { tx, rx }
+    }
+
+    pub fn len(&self) -> usize {
+        self.rx.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rx.is_empty()
+    }
+
+    /// pull one item without blocking; `None` once the stage is drained
+    pub fn try_pop(&self) -> Option<T> {
+        self.rx.try_recv().ok()
+    }
+
+    /// push one item without blocking, handing the item back if the stage
+    /// is already at capacity so the caller can apply backpressure
+    pub fn try_push(&self, item: T) -> Result<(), T> {
+        self.tx.try_send(item).map_err(|e| e.into_inner())
+    }
+
+    /// best-effort, non-blocking bulk push: items past the stage's
+    /// capacity are dropped. Callers that need backpressure instead of
+    /// truncation should push each item through `push_async`.
+    pub fn extend(&self, items: impl IntoIterator<Item = T>) {
+        for item in items {
+            let _ = self.try_push(item);
+        }
+    }
+
+    /// push one item, yielding until the stage has room instead of
+    /// dropping it; the backpressured counterpart to `try_push`
+    pub async fn push_async(&self, item: T) {
+        let _ = self.tx.send_async(item).await;
+    }
+}
+
 /// number that once for a concurrent future poll
 pub struct AppArg {
     pub round_req: usize,       // consume req one time
@@ -29,6 +110,15 @@ pub struct AppArg {
     pub profile_max: usize,     // maximal profile number
     pub round_yield_err: usize, //consume yield_err once upon a time
     pub round_result: usize,    //consume Entity once upon a time
+    pub request_buffer_bytes: usize, // byte budget for in-flight requests, bounds memory/socket usage
+    pub target_round_ms: u64,  // tranquilizer target: wall-clock duration an exec_all batch should take
+    pub round_req_step: usize, // tranquilizer: how much to grow round_req by when batches finish early
+    pub round_req_cap: usize,  // tranquilizer: ceiling the adaptive round_req may grow to
+    pub max_retries: u32,      // retryable fetches give up and go to yield_err after this many retries
+    pub retry_backoff_base_ms: u64, // base for the exponential `able = now + base * 2^retries` delay
+    pub retry_statuses: Vec<u16>, // HTTP statuses treated as transient failures, same as a connection error
+    pub worker_threads: usize, // size of the dedicated runtime fetch/profile workers run on
+    pub shutdown_grace_ms: u64, // how long a SIGINT/SIGTERM drain waits before force-aborting tasks
     pub skip_history: bool,
 }
 
@@ -45,6 +135,15 @@ impl Default for AppArg {
             profile_max: 10000,
             round_yield_err: 100,
             round_result: 100,
+            request_buffer_bytes: 200 * 1024 * 1024,
+            target_round_ms: 500,
+            round_req_step: 10,
+            round_req_cap: 1000,
+            max_retries: 3,
+            retry_backoff_base_ms: 500,
+            retry_statuses: vec![429, 500, 502, 503, 504],
+            worker_threads: 4,
+            shutdown_grace_ms: 5000,
             skip_history: false,
         }
     }
@@ -52,33 +151,104 @@ impl Default for AppArg {
 
 pub struct App<Entity> {
     pub uas: Arc<Vec<UserAgent>>,
+
+    #[cfg(feature = "legacy-vec-pipeline")]
     pub task: Arc<Mutex<Vec<Task>>>,
+    #[cfg(feature = "legacy-vec-pipeline")]
     pub profile: Arc<Mutex<Vec<Profile>>>,
+    #[cfg(feature = "legacy-vec-pipeline")]
     pub req: Arc<Mutex<Vec<Request>>>,
+    #[cfg(feature = "legacy-vec-pipeline")]
     pub req_tmp: Arc<Mutex<Vec<Request>>>,
+    #[cfg(feature = "legacy-vec-pipeline")]
     pub res: Arc<Mutex<Vec<Response>>>,
+    #[cfg(feature = "legacy-vec-pipeline")]
     pub result: Arc<Mutex<Vec<Entity>>>,
+    #[cfg(feature = "legacy-vec-pipeline")]
     pub yield_err: Arc<Mutex<Vec<String>>>,
-    pub fut_res: Arc<Mutex<Vec<(u64, task::JoinHandle<()>)>>>,
-    pub fut_profile: Arc<Mutex<Vec<(u64, task::JoinHandle<()>)>>>,
+
+    #[cfg(not(feature = "legacy-vec-pipeline"))]
+    pub task: Stage<Task>,
+    #[cfg(not(feature = "legacy-vec-pipeline"))]
+    pub profile: Stage<Profile>,
+    #[cfg(not(feature = "legacy-vec-pipeline"))]
+    pub req: Stage<Request>,
+    #[cfg(not(feature = "legacy-vec-pipeline"))]
+    pub req_tmp: Stage<Request>,
+    #[cfg(not(feature = "legacy-vec-pipeline"))]
+    pub res: Stage<Response>,
+    #[cfg(not(feature = "legacy-vec-pipeline"))]
+    pub result: Stage<Entity>,
+    #[cfg(not(feature = "legacy-vec-pipeline"))]
+    pub yield_err: Stage<String>,
+
+    pub fut_res: JoinHandleList,
+    pub fut_profile: JoinHandleList,
+    // byte-budget semaphore bounding how much outstanding request work is in
+    // flight at once; sized from `AppArg::request_buffer_bytes` in `run`
+    pub sem: Arc<Semaphore>,
+    // tranquilizer: sliding window (ms) of the last exec_all batch durations
+    pub round_durations: Arc<Mutex<Vec<u64>>>,
+    // tranquilizer: adaptive working value of round_req, nudged each round
+    // to converge on `AppArg::target_round_ms`
+    pub adaptive_round_req: Arc<AtomicUsize>,
+}
+
+impl<Entity> Default for App<Entity> {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl<'a, Entity> App<Entity> {
     pub fn new() -> Self {
+        let args = AppArg::default();
         App {
             uas: Arc::new(Vec::new()),
+
+            #[cfg(feature = "legacy-vec-pipeline")]
             task: Arc::new(Mutex::new(Vec::new())),
+            #[cfg(feature = "legacy-vec-pipeline")]
             profile: Arc::new(Mutex::new(Vec::new())),
+            #[cfg(feature = "legacy-vec-pipeline")]
             req: Arc::new(Mutex::new(Vec::new())),
+            #[cfg(feature = "legacy-vec-pipeline")]
             req_tmp: Arc::new(Mutex::new(Vec::new())),
+            #[cfg(feature = "legacy-vec-pipeline")]
             res: Arc::new(Mutex::new(Vec::new())),
+            #[cfg(feature = "legacy-vec-pipeline")]
             result: Arc::new(Mutex::new(Vec::new())),
+            #[cfg(feature = "legacy-vec-pipeline")]
             yield_err: Arc::new(Mutex::new(Vec::new())),
+
+            #[cfg(not(feature = "legacy-vec-pipeline"))]
+            task: Stage::bounded(args.round_task * 4),
+            #[cfg(not(feature = "legacy-vec-pipeline"))]
+            profile: Stage::bounded(args.profile_max),
+            #[cfg(not(feature = "legacy-vec-pipeline"))]
+            req: Stage::bounded(args.round_req_max * 4),
+            #[cfg(not(feature = "legacy-vec-pipeline"))]
+            req_tmp: Stage::bounded(args.round_req_max),
+            #[cfg(not(feature = "legacy-vec-pipeline"))]
+            res: Stage::bounded(args.round_res * 4),
+            #[cfg(not(feature = "legacy-vec-pipeline"))]
+            result: Stage::bounded(args.round_result * 4),
+            #[cfg(not(feature = "legacy-vec-pipeline"))]
+            yield_err: Stage::bounded(args.round_yield_err * 4),
+
             fut_res: Arc::new(Mutex::new(Vec::new())),
             fut_profile: Arc::new(Mutex::new(Vec::new())),
+            sem: Arc::new(Semaphore::new(args.request_buffer_bytes)),
+            round_durations: Arc::new(Mutex::new(Vec::new())),
+            adaptive_round_req: Arc::new(AtomicUsize::new(args.round_req)),
         }
     }
 
+    /// current adaptive batch size chosen by the tranquilizer, useful for logging
+    pub fn current_round_req(&self) -> usize {
+        self.adaptive_round_req.load(Ordering::Relaxed)
+    }
+
     pub async fn run<C>(
         &'a mut self,
         args: Option<AppArg>,
@@ -86,17 +256,34 @@ impl<'a, Entity> App<Entity> {
         mware: Option<&'a dyn MiddleWare<Entity>>,
         pline: Option<&'a dyn Pipeline<Entity, C>>,
     ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-        // signal handling initial
+        // signal handling initial: both Ctrl+C and a `kill` default to the
+        // same graceful-drain branch below
         let term = Arc::new(AtomicUsize::new(0));
-        const SIGINT: usize = signal_hook::SIGINT as usize;
-        signal_flag::register_usize(signal_hook::SIGINT, Arc::clone(&term), SIGINT).unwrap();
-
-        let args = match args {
-            Some(para) => para,
-            None => AppArg::default(),
-        };
-        let default_pl = PipelineDefault::new();
+        const SIGINT: usize = signal_hook::consts::SIGINT as usize;
+        const SIGTERM: usize = signal_hook::consts::SIGTERM as usize;
+        signal_flag::register_usize(signal_hook::consts::SIGINT, Arc::clone(&term), SIGINT)
+            .unwrap();
+        signal_flag::register_usize(signal_hook::consts::SIGTERM, Arc::clone(&term), SIGTERM)
+            .unwrap();
+
+        let args = args.unwrap_or_default();
+        self.sem = Arc::new(Semaphore::new(args.request_buffer_bytes));
+        // App::new() seeds this from AppArg::default(); reseed from the
+        // caller-supplied args so a custom round_req isn't silently ignored
+        self.adaptive_round_req
+            .store(args.round_req, Ordering::Relaxed);
+        // dedicated multi-threaded runtime the fetch/profile workers are
+        // spawned onto, sized independently from whatever runtime is
+        // driving this `run` future
+        let worker_rt = tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(args.worker_threads.max(1))
+            .enable_all()
+            .build()?;
+        let default_pl: &dyn Pipeline<Entity, C> = &PipelineDefault::new();
         let default_mw = MiddleWareDefault::new();
+        // resolved once; used for the request-start hook below, same
+        // fallback-to-default pattern the response-parsing hook already uses
+        let active_mw: &dyn MiddleWare<Entity> = mware.unwrap_or(&default_mw);
         spd.open_spider(self);
         //skip the history and start new fields to staart with, some Profile required
         if args.skip_history {
@@ -105,7 +292,14 @@ impl<'a, Entity> App<Entity> {
             let uas = self.uas.clone();
             Profile::exec_all(spd, self.profile.clone(), uri, 7, uas).await;
             let tasks = spd.entry_task().unwrap();
+            #[cfg(feature = "legacy-vec-pipeline")]
             self.task.lock().unwrap().extend(tasks);
+            #[cfg(not(feature = "legacy-vec-pipeline"))]
+            for t in tasks {
+                // wait for room instead of Stage::extend's silent drop, so a
+                // large entry_task() batch is backpressured, not truncated
+                self.task.push_async(t).await;
+            }
         }
 
         loop {
@@ -115,39 +309,60 @@ impl<'a, Entity> App<Entity> {
                 .as_secs();
 
             match term.load(Ordering::Relaxed) {
-                SIGINT => {
-                    // receive the Ctrl+c signal
-                    // by default  request  task profile and result yield err are going to stroed into
-                    // file
+                SIGINT | SIGTERM => {
+                    // received Ctrl+C or a `kill`: by default request, task,
+                    // profile and result/yield_err are going to be stored
+                    // into file, so drain everything in flight first
+
+                    //finish remaining futures, both fetch and profile ones;
+                    //collect abort handles first so a stuck batch can still
+                    //be force-quit once the grace period elapses
+                    let mut handles = Vec::new();
+                    while let Some((_, jh)) = self.fut_res.lock().unwrap().pop() {
+                        handles.push(jh);
+                    }
+                    while let Some((_, jh)) = self.fut_profile.lock().unwrap().pop() {
+                        handles.push(jh);
+                    }
+                    let abort_handles: Vec<_> = handles.iter().map(|h| h.abort_handle()).collect();
 
-                    //finish remaining futures
-                    let mut v = Vec::new();
-                    while let Some(res) = self.fut_res.lock().unwrap().pop() {
-                        //res.await;
-                        v.push(res.1);
+                    tokio::select! {
+                        _ = join_all(handles) => {
+                            info!("drained outstanding futures cleanly");
+                        }
+                        _ = tokio::time::sleep(std::time::Duration::from_millis(args.shutdown_grace_ms)) => {
+                            info!("shutdown grace period elapsed; force-quitting outstanding tasks");
+                            for ah in abort_handles {
+                                ah.abort();
+                            }
+                        }
                     }
-                    join_all(v).await;
 
                     // dispath them
                     match mware {
-                        Some(ware) => Response::parse_all(self, 99999999, spd, ware),
-                        None => Response::parse_all(self, 99999999, spd, &default_mw),
+                        Some(ware) => Response::parse_all(self, 99999999, spd, ware, args.max_retries, args.retry_backoff_base_ms),
+                        None => Response::parse_all(self, 99999999, spd, &default_mw, args.max_retries, args.retry_backoff_base_ms),
                     }
 
                     //store them
                     match pline {
                         Some(pl) => {
-                            pl.process_item(&mut self.result);
-                            pl.process_yielderr(&mut self.yield_err);
+                            pl.process_item(&self.result);
+                            pl.process_yielderr(&self.yield_err);
                         }
                         None => {
-                            default_pl.process_item(&mut self.result);
-                            default_pl.process_yielderr(&mut self.yield_err);
+                            default_pl.process_item(&self.result);
+                            default_pl.process_yielderr(&self.yield_err);
                         }
                     }
+                    active_mw.on_finish(self);
                     spd.close_spider(self);
+                    // otherwise the next iteration re-enters this arm with
+                    // empty fut_res/fut_profile and spins forever
+                    break;
                 }
 
+                #[cfg(feature = "legacy-vec-pipeline")]
                 0 => {
                     // if all task request and other things are done the quit
                     if self.yield_err.lock().unwrap().is_empty()
@@ -165,10 +380,26 @@ impl<'a, Entity> App<Entity> {
                     if self.req_tmp.lock().unwrap().len() <= args.round_req_min {
                         // cached request is not enough
                         for _ in 0..self.req.lock().unwrap().len() {
-                            let req = self.req.lock().unwrap().pop().unwrap();
+                            let mut req = self.req.lock().unwrap().pop().unwrap();
                             if req.able <= now {
-                                // put the request into cbase_req_tmp
-                                self.req_tmp.lock().unwrap().push(req);
+                                // give middleware a chance to allow/drop/reschedule
+                                // before the request is actually dispatched
+                                match active_mw.on_request(&req) {
+                                    RequestAction::Allow => {
+                                        // put the request into cbase_req_tmp
+                                        self.req_tmp.lock().unwrap().push(req);
+                                    }
+                                    RequestAction::Drop => {}
+                                    RequestAction::Reschedule(delay_secs) => {
+                                        req.able = now + delay_secs;
+                                        self.req.lock().unwrap().push(req);
+                                    }
+                                }
+                            } else {
+                                // not due yet (e.g. a backed-off retry): keep it
+                                // queued, at the front so Vec::pop doesn't hand
+                                // it straight back this round
+                                self.req.lock().unwrap().insert(0, req);
                             }
 
                             if self.req_tmp.lock().unwrap().len() > args.round_req_max {
@@ -177,18 +408,105 @@ impl<'a, Entity> App<Entity> {
                         }
                     }
 
-                    //take req out to finish
+                    //take req out to finish, staying inside the in-flight byte budget;
+                    //once the semaphore runs dry we stop draining req_tmp this round
+                    //instead of spawning, which gives natural backpressure
                     let mut futs = Vec::new();
-                    let len = args.round_req.min(self.req_tmp.lock().unwrap().len());
-                    vec![0; len].iter().for_each(|_| {
-                        let req = self.req_tmp.lock().unwrap().pop().unwrap();
-                        futs.push(req);
-                    });
-                    let tbase_res = self.res.clone();
-                    let john = task::spawn(async move {
-                        Client::exec_all(futs, tbase_res).await;
-                    });
-                    self.fut_res.lock().unwrap().push((now, john));
+                    let mut permits = Vec::new();
+                    let len = self.current_round_req().min(self.req_tmp.lock().unwrap().len());
+                    for _ in 0..len {
+                        let req = match self.req_tmp.lock().unwrap().pop() {
+                            Some(req) => req,
+                            None => break,
+                        };
+                        let cost = estimate_request_bytes(&req).max(1) as u32;
+                        if cost as usize > args.request_buffer_bytes {
+                            // no amount of waiting grants this many permits;
+                            // looping it back would starve req_tmp forever
+                            self.yield_err.lock().unwrap().push(format!(
+                                "{}: estimated size {} exceeds request_buffer_bytes budget {}",
+                                req.uri, cost, args.request_buffer_bytes
+                            ));
+                            continue;
+                        }
+                        match self.sem.clone().try_acquire_many_owned(cost) {
+                            Ok(permit) => {
+                                permits.push(permit);
+                                futs.push(req);
+                            }
+                            Err(_) => {
+                                // req_tmp is drained via Vec::pop (LIFO); pushing
+                                // this request back onto the end would hand it
+                                // right back out first next round, starving
+                                // everything else behind it. Insert at the front
+                                // instead so it's retried only once the rest of
+                                // req_tmp has had a turn.
+                                self.req_tmp.lock().unwrap().insert(0, req);
+                                break;
+                            }
+                        }
+                    }
+                    if !futs.is_empty() {
+                        let tbase_res = self.res.clone();
+                        let durations = self.round_durations.clone();
+                        let adaptive = self.adaptive_round_req.clone();
+                        let target_ms = args.target_round_ms;
+                        let step = args.round_req_step;
+                        let cap = args.round_req_cap;
+                        let treq = self.req.clone();
+                        let max_retries = args.max_retries;
+                        let backoff_base = args.retry_backoff_base_ms;
+                        let retry_statuses = Arc::new(args.retry_statuses.clone());
+                        let john = worker_rt.spawn(async move {
+                            let started = std::time::Instant::now();
+                            // permits are released by exec_all as each fetch
+                            // completes. transient failures get their
+                            // `able` pushed into the future and are requeued onto
+                            // treq for a retry; only yield_err once max_retries
+                            // is exceeded.
+                            Client::exec_all(
+                                futs,
+                                tbase_res,
+                                permits,
+                                treq,
+                                max_retries,
+                                backoff_base,
+                                retry_statuses,
+                            )
+                            .await;
+                            let elapsed_ms = started.elapsed().as_millis() as u64;
+
+                            // tranquilizer: keep a sliding window of the last 20
+                            // batch durations and nudge round_req towards the
+                            // target, backing off multiplicatively when too slow
+                            let avg_ms = {
+                                let mut hist = durations.lock().unwrap();
+                                hist.push(elapsed_ms);
+                                if hist.len() > 20 {
+                                    hist.remove(0);
+                                }
+                                hist.iter().sum::<u64>() / hist.len() as u64
+                            };
+                            // fetch_update instead of load-then-store: concurrent
+                            // batches on worker_rt can finish close together, and a
+                            // plain load/store pair would lose one's update
+                            if avg_ms < target_ms {
+                                let _ = adaptive.fetch_update(
+                                    Ordering::Relaxed,
+                                    Ordering::Relaxed,
+                                    |cur| Some((cur + step).min(cap)),
+                                );
+                            } else if avg_ms > target_ms {
+                                let _ = adaptive.fetch_update(
+                                    Ordering::Relaxed,
+                                    Ordering::Relaxed,
+                                    |cur| Some((cur / 2).max(1)),
+                                );
+                            }
+
+                        });
+                        self.fut_res.lock().unwrap().push((now, john));
+                    }
 
                     // before we construct request check profile first
                     let less = self.profile.lock().unwrap().len() <= args.profile_min;
@@ -199,7 +517,7 @@ impl<'a, Entity> App<Entity> {
                         let uas = self.uas.clone();
                         let uri = spd.entry_profile().unwrap();
                         let pfile = self.profile.clone();
-                        let johp = task::spawn(async move {
+                        let johp = worker_rt.spawn(async move {
                             Profile::exec_all(spd, pfile, uri, 7, uas).await;
                         });
                         self.fut_profile.lock().unwrap().push((now, johp));
@@ -208,21 +526,19 @@ impl<'a, Entity> App<Entity> {
                     // parse response
                     //extract the parseResult
                     match mware {
-                        Some(ware) => Response::parse_all(self, args.round_res, spd, ware),
-                        None => Response::parse_all(self, args.round_res, spd, &default_mw),
+                        Some(ware) => Response::parse_all(self, args.round_res, spd, ware, args.max_retries, args.retry_backoff_base_ms),
+                        None => Response::parse_all(self, args.round_res, spd, &default_mw, args.max_retries, args.retry_backoff_base_ms),
                     }
 
                     //pipeline put out yield_parse_err and Entity
                     if self.yield_err.lock().unwrap().len() > args.round_yield_err {
-                        match pline {
-                            Some(pl) => pl.process_yielderr(&mut self.yield_err),
-                            None => {}
+                        if let Some(pl) = pline {
+                            pl.process_yielderr(&self.yield_err);
                         }
                     }
                     if self.result.lock().unwrap().len() > args.round_result {
-                        match pline {
-                            Some(pl) => pl.process_item(&mut self.result),
-                            None => {}
+                        if let Some(pl) = pline {
+                            pl.process_item(&self.result);
                         }
                     }
 
@@ -241,10 +557,202 @@ impl<'a, Entity> App<Entity> {
                     Client::join(self.fut_res.clone(), self.fut_profile.clone()).await;
                 }
 
+                #[cfg(not(feature = "legacy-vec-pipeline"))]
+                0 => {
+                    // if all task request and other things are done the quit
+                    if self.yield_err.is_empty()
+                        && self.req.is_empty()
+                        && self.task.is_empty()
+                        && self.result.is_empty()
+                        && self.profile.is_empty()
+                    {
+                        info!("All work is Done. exit gracefully");
+                        break;
+                    }
+
+                    // consume valid request in req_tmp
+                    // if not enough take them from req
+                    if self.req_tmp.len() <= args.round_req_min {
+                        // cached request is not enough
+                        for _ in 0..self.req.len() {
+                            let mut req = match self.req.try_pop() {
+                                Some(req) => req,
+                                None => break,
+                            };
+                            if req.able <= now {
+                                // give middleware a chance to allow/drop/reschedule
+                                // before the request is actually dispatched
+                                match active_mw.on_request(&req) {
+                                    RequestAction::Allow => {
+                                        if let Err(req) = self.req_tmp.try_push(req) {
+                                            // req_tmp is at capacity; backpressure, stop draining
+                                            // and put the request back onto req
+                                            self.req.push_async(req).await;
+                                            break;
+                                        }
+                                    }
+                                    RequestAction::Drop => {}
+                                    RequestAction::Reschedule(delay_secs) => {
+                                        req.able = now + delay_secs;
+                                        // same backpressure as the Allow arm above:
+                                        // wait for room rather than dropping the request
+                                        self.req.push_async(req).await;
+                                    }
+                                }
+                            } else {
+                                // not due yet (e.g. a backed-off retry): keep it queued
+                                self.req.push_async(req).await;
+                            }
+
+                            if self.req_tmp.len() > args.round_req_max {
+                                break;
+                            }
+                        }
+                    }
+
+                    //take req out to finish, staying inside the in-flight byte budget;
+                    //once the semaphore runs dry we stop draining req_tmp this round
+                    //instead of spawning, which gives natural backpressure
+                    let mut futs = Vec::new();
+                    let mut permits = Vec::new();
+                    let len = self.current_round_req().min(self.req_tmp.len());
+                    for _ in 0..len {
+                        let req = match self.req_tmp.try_pop() {
+                            Some(req) => req,
+                            None => break,
+                        };
+                        let cost = estimate_request_bytes(&req).max(1) as u32;
+                        match self.sem.clone().try_acquire_many_owned(cost) {
+                            Ok(permit) => {
+                                permits.push(permit);
+                                futs.push(req);
+                            }
+                            Err(_) => {
+                                // out of budget this round; put it back for next time
+                                let _ = self.req_tmp.try_push(req);
+                                break;
+                            }
+                        }
+                    }
+                    if !futs.is_empty() {
+                        let tbase_res = self.res.clone();
+                        let durations = self.round_durations.clone();
+                        let adaptive = self.adaptive_round_req.clone();
+                        let target_ms = args.target_round_ms;
+                        let step = args.round_req_step;
+                        let cap = args.round_req_cap;
+                        let treq = self.req.clone();
+                        let max_retries = args.max_retries;
+                        let backoff_base = args.retry_backoff_base_ms;
+                        let retry_statuses = Arc::new(args.retry_statuses.clone());
+                        let john = worker_rt.spawn(async move {
+                            let started = std::time::Instant::now();
+                            // permits are released by exec_all as each fetch
+                            // completes. transient failures get their
+                            // `able` pushed into the future and are requeued onto
+                            // treq for a retry; only yield_err once max_retries
+                            // is exceeded.
+                            Client::exec_all(
+                                futs,
+                                tbase_res,
+                                permits,
+                                treq,
+                                max_retries,
+                                backoff_base,
+                                retry_statuses,
+                            )
+                            .await;
+                            let elapsed_ms = started.elapsed().as_millis() as u64;
+
+                            // tranquilizer: keep a sliding window of the last 20
+                            // batch durations and nudge round_req towards the
+                            // target, backing off multiplicatively when too slow
+                            let avg_ms = {
+                                let mut hist = durations.lock().unwrap();
+                                hist.push(elapsed_ms);
+                                if hist.len() > 20 {
+                                    hist.remove(0);
+                                }
+                                hist.iter().sum::<u64>() / hist.len() as u64
+                            };
+                            // fetch_update instead of load-then-store: concurrent
+                            // batches on worker_rt can finish close together, and a
+                            // plain load/store pair would lose one's update
+                            if avg_ms < target_ms {
+                                let _ = adaptive.fetch_update(
+                                    Ordering::Relaxed,
+                                    Ordering::Relaxed,
+                                    |cur| Some((cur + step).min(cap)),
+                                );
+                            } else if avg_ms > target_ms {
+                                let _ = adaptive.fetch_update(
+                                    Ordering::Relaxed,
+                                    Ordering::Relaxed,
+                                    |cur| Some((cur / 2).max(1)),
+                                );
+                            }
+
+                        });
+                        self.fut_res.lock().unwrap().push((now, john));
+                    }
+
+                    // before we construct request check profile first
+                    let less = self.profile.len() <= args.profile_min;
+                    let exceed =
+                        !less && self.profile.len() <= args.profile_max && now % 3 == 1;
+                    if exceed || less {
+                        let uas = self.uas.clone();
+                        let uri = spd.entry_profile().unwrap();
+                        let pfile = self.profile.clone();
+                        let johp = worker_rt.spawn(async move {
+                            Profile::exec_all(spd, pfile, uri, 7, uas).await;
+                        });
+                        self.fut_profile.lock().unwrap().push((now, johp));
+                    }
+
+                    // parse response
+                    //extract the parseResult
+                    match mware {
+                        Some(ware) => Response::parse_all(self, args.round_res, spd, ware, args.max_retries, args.retry_backoff_base_ms),
+                        None => Response::parse_all(self, args.round_res, spd, &default_mw, args.max_retries, args.retry_backoff_base_ms),
+                    }
+
+                    //pipeline put out yield_parse_err and Entity
+                    if self.yield_err.len() > args.round_yield_err {
+                        if let Some(pl) = pline {
+                            pl.process_yielderr(&self.yield_err);
+                        }
+                    }
+                    if self.result.len() > args.round_result {
+                        if let Some(pl) = pline {
+                            pl.process_item(&self.result);
+                        }
+                    }
+
+                    // count for profiles length if not more than round_task_min
+                    if args.round_task_min > self.profile.len() {
+                        // not enough profile to construct request
+                        // await the spawned task doe
+                        let jh = self.fut_profile.lock().unwrap().pop().unwrap();
+                        jh.1.await.unwrap();
+                    }
+
+                    // construct request
+                    Request::gen(self, args.round_task);
+
+                    //join the older tokio-task
+                    Client::join(self.fut_res.clone(), self.fut_profile.clone()).await;
+                }
+
                 _ => unreachable!(),
             }
         }
 
+        // worker_rt is a local Runtime; dropping one from inside the async
+        // context that's driving it panics, so shut it down explicitly
+        // instead of letting scope-exit Drop do it
+        worker_rt.shutdown_background();
+
         Ok(())
     }
 }
@@ -0,0 +1,10 @@
+pub mod component;
+pub mod engine;
+pub mod macros;
+
+pub use component::{Client, Profile, Request, Response, Task, UserAgent};
+pub use engine::{App, AppArg};
+pub use macros::{
+    MiddleWare, MiddleWareDefault, Pipeline, PipelineDefault, RequestAction, ResponseAction,
+    Spider,
+};
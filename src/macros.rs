@@ -0,0 +1,91 @@
+use crate::component::{Request, Response, Task};
+use crate::engine::App;
+#[cfg(not(feature = "legacy-vec-pipeline"))]
+use crate::engine::Stage;
+#[cfg(feature = "legacy-vec-pipeline")]
+use std::sync::{Arc, Mutex};
+
+/// outcome of `MiddleWare::on_request`: whether a request is dispatched
+/// as-is, dropped, or pushed back onto the queue for a later attempt
+pub enum RequestAction {
+    Allow,
+    Drop,
+    Reschedule(u64), // delay in seconds before the request becomes `able` again
+}
+
+/// outcome of `MiddleWare::on_response`: whether a fetched response is
+/// handed to `Spider::parse`, retried, or thrown away
+pub enum ResponseAction {
+    Continue,
+    Retry,
+    Discard,
+}
+
+/// full request/response lifecycle hooks a crawl can plug into. every
+/// method has a no-op default so implementors only override the phases
+/// they care about.
+pub trait MiddleWare<Entity> {
+    fn on_request(&self, _req: &Request) -> RequestAction {
+        RequestAction::Allow
+    }
+    fn on_response(&self, _res: &Response) -> ResponseAction {
+        ResponseAction::Continue
+    }
+    fn on_parse_result(&self, _items: &mut Vec<Entity>, _errs: &mut Vec<String>) {}
+    fn on_finish(&self, _app: &App<Entity>) {}
+}
+
+#[derive(Default)]
+pub struct MiddleWareDefault;
+
+impl MiddleWareDefault {
+    pub fn new() -> Self {
+        Self
+    }
+}
+
+impl<Entity> MiddleWare<Entity> for MiddleWareDefault {}
+
+/// sink for parsed entities and parse errors; implementors decide where
+/// results land (database, file, stdout, ...). `C` is whatever
+/// connection/config type a concrete pipeline needs to get there.
+pub trait Pipeline<Entity, C> {
+    #[cfg(feature = "legacy-vec-pipeline")]
+    fn process_item(&self, items: &Arc<Mutex<Vec<Entity>>>) {
+        items.lock().unwrap().clear();
+    }
+    #[cfg(not(feature = "legacy-vec-pipeline"))]
+    fn process_item(&self, items: &Stage<Entity>) {
+        while items.try_pop().is_some() {}
+    }
+
+    #[cfg(feature = "legacy-vec-pipeline")]
+    fn process_yielderr(&self, errs: &Arc<Mutex<Vec<String>>>) {
+        errs.lock().unwrap().clear();
+    }
+    #[cfg(not(feature = "legacy-vec-pipeline"))]
+    fn process_yielderr(&self, errs: &Stage<String>) {
+        while errs.try_pop().is_some() {}
+    }
+}
+
+#[derive(Default)]
+pub struct PipelineDefault;
+
+impl PipelineDefault {
+    pub fn new() -> Self {
+        Self
+    }
+}
+
+impl<Entity, C> Pipeline<Entity, C> for PipelineDefault {}
+
+/// a single crawl's entry points and parsing logic; `open_spider`/`close_spider`
+/// bookend a run and default to no-ops
+pub trait Spider<Entity>: Sync {
+    fn entry_profile(&self) -> Result<String, Box<dyn std::error::Error + Send + Sync>>;
+    fn entry_task(&self) -> Result<Vec<Task>, Box<dyn std::error::Error + Send + Sync>>;
+    fn parse(&self, res: &Response) -> (Vec<Entity>, Vec<Task>, Vec<String>);
+    fn open_spider(&self, _app: &App<Entity>) {}
+    fn close_spider(&self, _app: &App<Entity>) {}
+}